@@ -0,0 +1,181 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{sync_channel, Receiver, RecvTimeoutError, SyncSender};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use tracing::{debug, warn};
+
+use crate::Measurement;
+
+/// The `--influx-*` options, once we've decided to push.
+#[derive(Debug, Clone)]
+pub struct InfluxConfig {
+  /// Base URL of the InfluxDB server, e.g. `http://host:8086`.
+  pub url: String,
+
+  /// Database to write into.
+  pub db: String,
+
+  /// Measurement name to use in the line protocol.
+  pub measurement: String,
+}
+
+/// A reading and the instant it was taken.
+type QueuedReading = (Measurement, SystemTime);
+
+/// Flush once this many lines have accumulated.
+const BATCH_SIZE: usize = 64;
+
+/// Flush a partial batch at least this often.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Bounded queue depth; a lagging writer drops readings rather than blocking.
+const QUEUE_CAPACITY: usize = 256;
+
+/// Cap on lines retained across failed flushes during an outage.
+const MAX_BUFFERED_LINES: usize = 256;
+
+/// Sender into the writer thread, plus its handle for flush-on-shutdown.
+pub struct InfluxWriter {
+  pub tx: SyncSender<QueuedReading>,
+  pub handle: JoinHandle<()>,
+}
+
+/// Spawn the InfluxDB writer thread.
+pub fn spawn_writer(config: InfluxConfig, error_count: Arc<AtomicUsize>) -> InfluxWriter {
+  let (tx, rx) = sync_channel(QUEUE_CAPACITY);
+  let handle = std::thread::spawn(move || writer_thread(config, rx, error_count));
+  InfluxWriter { tx, handle }
+}
+
+/// Encode one reading as a line of InfluxDB line protocol.
+fn encode_line(config: &InfluxConfig, m: &Measurement, timestamp: SystemTime) -> String {
+  let host = std::env::var("HOSTNAME").unwrap_or_else(|_| "unknown".to_string());
+  let timestamp_ns = timestamp
+    .duration_since(UNIX_EPOCH)
+    .map(|d| d.as_nanos())
+    .unwrap_or(0);
+
+  format!(
+    "{measurement},host={host} \
+     mass_pm1={},mass_pm25={},mass_pm4={},mass_pm10={},\
+     number_pm05={},number_pm1={},number_pm25={},number_pm4={},number_pm10={},\
+     typical_particle_size={} {timestamp_ns}",
+    m.mass.pm1,
+    m.mass.pm25,
+    m.mass.pm4,
+    m.mass.pm10,
+    m.number.pm05,
+    m.number.pm1,
+    m.number.pm25,
+    m.number.pm4,
+    m.number.pm10,
+    m.typical_particle_size,
+    measurement = config.measurement,
+    host = host,
+    timestamp_ns = timestamp_ns,
+  )
+}
+
+/// POST a batch of encoded lines to the InfluxDB `/write` endpoint.
+fn flush(client: &reqwest::blocking::Client, config: &InfluxConfig, lines: &[String]) -> reqwest::Result<()> {
+  if lines.is_empty() {
+    return Ok(());
+  }
+
+  let url = format!("{}/write?db={}", config.url.trim_end_matches('/'), config.db);
+  let body = lines.join("\n");
+
+  let resp = client.post(&url).body(body).send()?;
+  resp.error_for_status()?;
+  Ok(())
+}
+
+fn writer_thread(
+  config: InfluxConfig,
+  rx: Receiver<QueuedReading>,
+  error_count: Arc<AtomicUsize>,
+) {
+  let client = reqwest::blocking::Client::new();
+  let mut lines: Vec<String> = Vec::with_capacity(BATCH_SIZE);
+  let mut last_flush = Instant::now();
+
+  loop {
+    let timeout = FLUSH_INTERVAL
+      .checked_sub(last_flush.elapsed())
+      .unwrap_or_default();
+
+    match rx.recv_timeout(timeout) {
+      Ok((m, timestamp)) => {
+        lines.push(encode_line(&config, &m, timestamp));
+      }
+
+      // The read thread has gone away; flush whatever is left and stop.
+      Err(RecvTimeoutError::Disconnected) => {
+        if let Err(e) = flush(&client, &config, &lines) {
+          warn!("InfluxDB flush failed on shutdown: {}", e);
+          error_count.fetch_add(1, Ordering::Relaxed);
+        }
+        break;
+      }
+
+      Err(RecvTimeoutError::Timeout) => {}
+    }
+
+    if !lines.is_empty() && (lines.len() >= BATCH_SIZE || last_flush.elapsed() >= FLUSH_INTERVAL) {
+      match flush(&client, &config, &lines) {
+        Ok(()) => {
+          debug!("flushed {} line(s) to InfluxDB", lines.len());
+          lines.clear();
+        }
+        Err(e) => {
+          // Keep the batch for the next attempt so a transient outage catches
+          // up once the server returns. Cap what we retain, dropping the oldest
+          // lines (counted) so a prolonged outage can't grow memory unbounded.
+          warn!("InfluxDB flush failed: {}", e);
+          error_count.fetch_add(1, Ordering::Relaxed);
+          if lines.len() > MAX_BUFFERED_LINES {
+            let overflow = lines.len() - MAX_BUFFERED_LINES;
+            lines.drain(0..overflow);
+            error_count.fetch_add(overflow, Ordering::Relaxed);
+          }
+        }
+      }
+      last_flush = Instant::now();
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{MassConcentration, NumberConcentration};
+
+  #[test]
+  fn encodes_exact_line_protocol() {
+    std::env::set_var("HOSTNAME", "testhost");
+
+    let config = InfluxConfig {
+      url: "http://localhost:8086".to_string(),
+      db: "air".to_string(),
+      measurement: "sps30".to_string(),
+    };
+
+    let m = Measurement {
+      mass: MassConcentration { pm1: 3.2, pm25: 4.1, pm4: 5.5, pm10: 6.7 },
+      number: NumberConcentration { pm05: 12.5, pm1: 1.1, pm25: 2.2, pm4: 3.3, pm10: 4.4 },
+      typical_particle_size: 0.6,
+    };
+
+    let timestamp = UNIX_EPOCH + Duration::from_nanos(1_700_000_000_000_000_000);
+
+    assert_eq!(
+      encode_line(&config, &m, timestamp),
+      "sps30,host=testhost \
+       mass_pm1=3.2,mass_pm25=4.1,mass_pm4=5.5,mass_pm10=6.7,\
+       number_pm05=12.5,number_pm1=1.1,number_pm25=2.2,number_pm4=3.3,number_pm10=4.4,\
+       typical_particle_size=0.6 1700000000000000000"
+    );
+  }
+}
@@ -1,10 +1,13 @@
+use std::collections::VecDeque;
 use std::path::PathBuf;
 use std::sync::{Arc, RwLock};
 use std::sync::atomic::{AtomicUsize, Ordering, AtomicBool};
+use std::sync::mpsc::SyncSender;
 use std::thread;
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use color_eyre::{Result, eyre::eyre};
+use crossbeam_channel::{Receiver, Sender};
 use rppal::uart::*;
 use serde::Serialize;
 use serde_json::json;
@@ -14,11 +17,18 @@ use structopt::StructOpt;
 use tracing::{instrument, info, error, warn, debug};
 use warp::Filter;
 
+mod influx;
+mod otlp;
+mod stats;
 mod util;
 
-const MASS_UNIT: &str = "μg/m3";
-const NUMBER_UNIT: &str = "1/cm3";
-const PARTICLE_SIZE_UNIT: &str = "μm";
+use influx::{InfluxConfig, InfluxWriter};
+use otlp::OtlpMetrics;
+use stats::Stats;
+
+pub(crate) const MASS_UNIT: &str = "μg/m3";
+pub(crate) const NUMBER_UNIT: &str = "1/cm3";
+pub(crate) const PARTICLE_SIZE_UNIT: &str = "μm";
 
 /// A safe read interval. New values are ostensibly available every 1s. We
 /// double this avoid repeatedly falling into some "data unavailable" loop since
@@ -38,6 +48,35 @@ struct Options {
   /// HTTP server port
   #[structopt(long, short, default_value = "8090", env = "SPS30_PORT")]
   port: u16,
+
+  /// InfluxDB base URL to push readings to, e.g. http://host:8086. When set,
+  /// each reading is also written to InfluxDB via line protocol.
+  #[structopt(long, env = "SPS30_INFLUX_URL")]
+  influx_url: Option<String>,
+
+  /// InfluxDB database to write into. Required when --influx-url is set.
+  #[structopt(long, env = "SPS30_INFLUX_DB")]
+  influx_db: Option<String>,
+
+  /// InfluxDB measurement name used in the line protocol.
+  #[structopt(long, default_value = "sps30", env = "SPS30_INFLUX_MEASUREMENT")]
+  influx_measurement: String,
+
+  /// OTLP collector endpoint, e.g. http://localhost:4317. When set, readings
+  /// are pushed to an OpenTelemetry collector as gauge instruments.
+  #[structopt(long, env = "SPS30_OTLP_ENDPOINT")]
+  otlp_endpoint: Option<String>,
+
+  /// Number of recent samples to retain in the in-memory history buffer
+  /// exposed at /history. The default is ~24 minutes at the 2s read interval.
+  #[structopt(long, default_value = "720", env = "SPS30_HISTORY_SIZE")]
+  history_size: usize,
+
+  /// Number of recent samples in the rolling window used for the min/max/mean
+  /// and percentile statistics. Kept separate from --history-size so disabling
+  /// the history buffer doesn't also disable the stats series.
+  #[structopt(long, default_value = "720", env = "SPS30_STATS_WINDOW")]
+  stats_window: usize,
 }
 
 #[derive(Debug, Serialize, Clone, Copy)]
@@ -103,6 +142,52 @@ impl Measurement {
   }
 }
 
+/// A single measurement tagged with the wall-clock time it was taken. These are
+/// fanned out from the read thread to the aggregator and retained in the
+/// `/history` ring buffer.
+#[derive(Debug, Serialize, Clone, Copy)]
+struct Sample {
+  /// Unix timestamp of the reading, in milliseconds.
+  timestamp: u64,
+
+  measurement: Measurement,
+}
+
+/// Channel depth between the read thread and the aggregator. The channel is
+/// bounded so a lagging aggregator applies backpressure; dropped samples are
+/// counted against `error_count` rather than blocking the sensor read loop.
+const SAMPLE_CHANNEL_CAPACITY: usize = 256;
+
+/// Drain samples from the read thread, keeping the latest value for `/json` and
+/// `/metrics` and a fixed-capacity ring buffer for `/history`.
+#[instrument(skip_all)]
+fn aggregator(
+  rx: Receiver<Sample>,
+  latest: Arc<RwLock<Option<Measurement>>>,
+  history: Arc<RwLock<VecDeque<Sample>>>,
+  stats: Arc<RwLock<Stats>>,
+  capacity: usize,
+) {
+  while let Ok(sample) = rx.recv() {
+    if let Ok(mut latest) = latest.write() {
+      *latest = Some(sample.measurement);
+    }
+
+    if capacity > 0 {
+      if let Ok(mut history) = history.write() {
+        while history.len() >= capacity {
+          history.pop_front();
+        }
+        history.push_back(sample);
+      }
+    }
+
+    if let Ok(mut stats) = stats.write() {
+      stats.record(&sample.measurement);
+    }
+  }
+}
+
 fn map_sps30_error<E, F>(e: sps30::Error<E, F>) -> color_eyre::eyre::Error
 where
   E: std::fmt::Debug,
@@ -114,8 +199,10 @@ where
 
 #[instrument(skip_all)]
 fn read_thread(
-  reading_lock: Arc<RwLock<Option<Measurement>>>,
+  sample_tx: Sender<Sample>,
   error_count: Arc<AtomicUsize>,
+  influx: Option<InfluxWriter>,
+  otlp_metrics: Option<Arc<OtlpMetrics>>,
   term: Arc<AtomicBool>,
   opts: &Options
 ) -> Result<()> {
@@ -149,17 +236,34 @@ fn read_thread(
     match sps30.read_measurement() {
       Ok(data) => {
         let m = Measurement::from_array(data);
-
-        let mut lock = match reading_lock.write() {
-          Ok(lock) => lock,
-          Err(e) => {
-            warn!("could not acquire lock: {:?}", e);
+        let now = SystemTime::now();
+
+        // Hand the reading off to the InfluxDB writer, if configured. The
+        // queue is bounded; a full or closed channel means the writer has
+        // fallen behind, so count the dropped reading rather than blocking.
+        if let Some(w) = &influx {
+          if w.tx.try_send((m, now)).is_err() {
+            warn!("InfluxDB queue full; dropping reading");
             error_count.fetch_add(1, Ordering::Relaxed);
-            continue;
           }
-        };
-
-        *lock = Some(m);
+        }
+
+        // Update the OTLP gauge instruments, if the collector is configured.
+        if let Some(metrics) = &otlp_metrics {
+          metrics.record(&m);
+        }
+
+        // Fan the sample out to the aggregator. The channel is bounded, so a
+        // backed-up aggregator drops the sample and it's counted rather than
+        // stalling the read loop.
+        let timestamp = now
+          .duration_since(UNIX_EPOCH)
+          .map(|d| d.as_millis() as u64)
+          .unwrap_or(0);
+        if sample_tx.try_send(Sample { timestamp, measurement: m }).is_err() {
+          warn!("aggregator queue full; dropping reading");
+          error_count.fetch_add(1, Ordering::Relaxed);
+        }
       },
 
       // Do nothing on an empty result.
@@ -178,12 +282,27 @@ fn read_thread(
     error!("could not stop measurements: {:?}", e);
   }
 
+  // Drop the sender so the writer sees the channel close, then wait for it to
+  // flush its buffered readings before we exit the process out from under it.
+  if let Some(w) = influx {
+    drop(w.tx);
+    if let Err(e) = w.handle.join() {
+      error!("influx writer panicked: {:?}", e);
+    }
+  }
+
+  // Flush the final OTLP batch; process::exit won't run the provider's Drop.
+  if let Some(metrics) = &otlp_metrics {
+    metrics.shutdown();
+  }
+
   std::process::exit(0);
 }
 
 fn export_measurement(
   exporter: &Exporter,
   measurement: Option<Measurement>,
+  stats: &Arc<RwLock<Stats>>,
   error_count: &Arc<AtomicUsize>,
   fatal_error_count: &Arc<AtomicUsize>
 ) -> String {
@@ -207,6 +326,38 @@ fn export_measurement(
     None => ()
   };
 
+  // Windowed statistics per concentration channel. Each histogram emits
+  // min/max/mean plus the p50/p90/p99 percentiles so dashboards can smooth the
+  // noisy second-to-second readings without aggregating client-side.
+  {
+    let stats = stats.read().unwrap();
+
+    macro_rules! export_stats {
+      ($metric:expr, $variant:expr, $unit:expr, $hist:expr) => {{
+        let hist = $hist;
+        if !hist.is_empty() {
+          export!(s, &format!("{}_min", $metric), hist.min() as f64, variant = $variant, unit = $unit);
+          export!(s, &format!("{}_max", $metric), hist.max() as f64, variant = $variant, unit = $unit);
+          export!(s, &format!("{}_mean", $metric), hist.mean() as f64, variant = $variant, unit = $unit);
+          export!(s, &format!("{}_p50", $metric), hist.percentile(50) as f64, variant = $variant, unit = $unit);
+          export!(s, &format!("{}_p90", $metric), hist.percentile(90) as f64, variant = $variant, unit = $unit);
+          export!(s, &format!("{}_p99", $metric), hist.percentile(99) as f64, variant = $variant, unit = $unit);
+        }
+      }};
+    }
+
+    export_stats!("sps30_mass_concentration", "PM1.0", MASS_UNIT, &stats.mass_pm1);
+    export_stats!("sps30_mass_concentration", "PM2.5", MASS_UNIT, &stats.mass_pm25);
+    export_stats!("sps30_mass_concentration", "PM4", MASS_UNIT, &stats.mass_pm4);
+    export_stats!("sps30_mass_concentration", "PM10", MASS_UNIT, &stats.mass_pm10);
+
+    export_stats!("sps30_number_concentration", "PM0.5", NUMBER_UNIT, &stats.number_pm05);
+    export_stats!("sps30_number_concentration", "PM1.0", NUMBER_UNIT, &stats.number_pm1);
+    export_stats!("sps30_number_concentration", "PM2.5", NUMBER_UNIT, &stats.number_pm25);
+    export_stats!("sps30_number_concentration", "PM4", NUMBER_UNIT, &stats.number_pm4);
+    export_stats!("sps30_number_concentration", "PM10", NUMBER_UNIT, &stats.number_pm10);
+  }
+
   export!(s, "sps30_error_count", error_count.load(Ordering::Relaxed) as f64);
   export!(s, "sps30_fatal_error_count", fatal_error_count.load(Ordering::Relaxed) as f64);
 
@@ -223,17 +374,63 @@ async fn main() -> Result<()> {
   let port = opts.port;
 
   let latest_reading_lock = Arc::new(RwLock::new(None));
+  let history = Arc::new(RwLock::new(VecDeque::with_capacity(opts.history_size)));
+  let stats = Arc::new(RwLock::new(Stats::new(opts.stats_window)));
   let error_count = Arc::new(AtomicUsize::new(0));
   let fatal_error_count = Arc::new(AtomicUsize::new(0));
 
   let term = Arc::new(AtomicBool::new(false));
   signal_hook::flag::register(signal_hook::consts::SIGTERM, Arc::clone(&term))?;
 
-  let thread_reading = Arc::clone(&latest_reading_lock);
+  // The read thread fans measurements out through this bounded channel; the
+  // aggregator owns the latest value and the rolling history buffer.
+  let (sample_tx, sample_rx) = crossbeam_channel::bounded(SAMPLE_CHANNEL_CAPACITY);
+  let aggregator_latest = Arc::clone(&latest_reading_lock);
+  let aggregator_history = Arc::clone(&history);
+  let aggregator_stats = Arc::clone(&stats);
+  let history_size = opts.history_size;
+  thread::spawn(move || {
+    aggregator(sample_rx, aggregator_latest, aggregator_history, aggregator_stats, history_size);
+  });
+
+  // Optionally spin up the InfluxDB push backend. It only starts if a URL and
+  // database were both provided.
+  let influx = match (&opts.influx_url, &opts.influx_db) {
+    (Some(url), Some(db)) => {
+      info!("pushing readings to InfluxDB at {}", url);
+      let config = InfluxConfig {
+        url: url.clone(),
+        db: db.clone(),
+        measurement: opts.influx_measurement.clone(),
+      };
+      Some(influx::spawn_writer(config, Arc::clone(&error_count)))
+    }
+    (Some(_), None) => {
+      warn!("--influx-url set without --influx-db; InfluxDB push disabled");
+      None
+    }
+    _ => None,
+  };
+
+  // Optionally stand up the OTLP pipeline. Initialised here inside the tokio
+  // runtime so its periodic push reader can be scheduled.
+  let otlp_metrics = match &opts.otlp_endpoint {
+    Some(endpoint) => {
+      info!("exporting metrics via OTLP to {}", endpoint);
+      Some(Arc::new(otlp::init(
+        endpoint,
+        &opts.device,
+        Arc::clone(&error_count),
+        Arc::clone(&fatal_error_count),
+      )?))
+    }
+    None => None,
+  };
+
   let thread_error_count = Arc::clone(&error_count);
   let thread_fatal_error_count = Arc::clone(&fatal_error_count);
   let thread_handle = thread::spawn(move || {
-    if let Err(e) = read_thread(thread_reading, thread_error_count, term, &opts) {
+    if let Err(e) = read_thread(sample_tx, thread_error_count, influx, otlp_metrics, term, &opts) {
       error!("read thread failed: {}", e);
       thread_fatal_error_count.fetch_add(1, Ordering::Relaxed);
     }
@@ -251,14 +448,22 @@ async fn main() -> Result<()> {
     }
   });
 
+  let history_lock = Arc::clone(&history);
+  let r_history = warp::path("history").map(move || {
+    let samples: Vec<Sample> = history_lock.read().unwrap().iter().copied().collect();
+    warp::reply::json(&samples)
+  });
+
   let exporter = Arc::new(Exporter::new());
   let metrics_lock = Arc::clone(&latest_reading_lock);
+  let metrics_stats = Arc::clone(&stats);
   let metrics_error_count = Arc::clone(&error_count);
   let metrics_fatal_error_count = Arc::clone(&fatal_error_count);
   let r_metrics = warp::path("metrics").map(move || {
     export_measurement(
       &exporter,
       *metrics_lock.read().unwrap(),
+      &metrics_stats,
       &metrics_error_count,
       &metrics_fatal_error_count
     )
@@ -266,7 +471,7 @@ async fn main() -> Result<()> {
 
   info!("starting exporter on port {}", port);
 
-  let routes = warp::get().and(r_json).or(r_metrics);
+  let routes = warp::get().and(r_json).or(r_metrics).or(r_history);
   tokio::spawn(warp::serve(routes).run(([0, 0, 0, 0], port)));
 
   match thread_handle_task.await {
@@ -0,0 +1,124 @@
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use color_eyre::Result;
+use opentelemetry::KeyValue;
+use opentelemetry::metrics::{Gauge, MeterProvider as _};
+use opentelemetry_sdk::Resource;
+use opentelemetry_sdk::metrics::SdkMeterProvider;
+
+use crate::{MASS_UNIT, Measurement, NUMBER_UNIT, PARTICLE_SIZE_UNIT};
+
+/// Gauge instruments plus the provider owning the periodic OTLP push reader.
+pub struct OtlpMetrics {
+  mass_concentration: Gauge<f64>,
+  number_concentration: Gauge<f64>,
+  typical_particle_size: Gauge<f64>,
+
+  /// Held so the periodic reader keeps running and flushes on shutdown.
+  _provider: SdkMeterProvider,
+}
+
+/// Build the OTLP pipeline and register instruments. Host and device resource
+/// attributes are attached once here. Must run inside the tokio runtime so the
+/// periodic reader can be scheduled.
+pub fn init(
+  endpoint: &str,
+  device: &Path,
+  error_count: Arc<AtomicUsize>,
+  fatal_error_count: Arc<AtomicUsize>,
+) -> Result<OtlpMetrics> {
+  let host = std::env::var("HOSTNAME").unwrap_or_else(|_| "unknown".to_string());
+  // The hardware serial would have to be read over the UART in `read_thread`,
+  // which isn't available here; the device path is a stand-in, so label it as
+  // such rather than implying it uniquely identifies the sensor.
+  let resource = Resource::new(vec![
+    KeyValue::new("service.name", "sps30-exporter"),
+    KeyValue::new("host.name", host),
+    KeyValue::new("sensor.device_path", device.display().to_string()),
+  ]);
+
+  let provider = opentelemetry_otlp::new_pipeline()
+    .metrics(opentelemetry_sdk::runtime::Tokio)
+    .with_exporter(
+      opentelemetry_otlp::new_exporter()
+        .tonic()
+        .with_endpoint(endpoint),
+    )
+    .with_resource(resource)
+    .build()?;
+
+  let meter = provider.meter("sps30-exporter");
+
+  let mass_concentration = meter.f64_gauge("sps30_mass_concentration").init();
+  let number_concentration = meter.f64_gauge("sps30_number_concentration").init();
+  let typical_particle_size = meter.f64_gauge("sps30_typical_particle_size").init();
+
+  // Monotonic counters observe the cumulative values already tracked by the
+  // read thread via the shared atomics.
+  let ec = Arc::clone(&error_count);
+  meter
+    .u64_observable_counter("sps30_error_count")
+    .with_callback(move |observer| {
+      observer.observe(ec.load(Ordering::Relaxed) as u64, &[])
+    })
+    .init();
+
+  let fec = Arc::clone(&fatal_error_count);
+  meter
+    .u64_observable_counter("sps30_fatal_error_count")
+    .with_callback(move |observer| {
+      observer.observe(fec.load(Ordering::Relaxed) as u64, &[])
+    })
+    .init();
+
+  Ok(OtlpMetrics {
+    mass_concentration,
+    number_concentration,
+    typical_particle_size,
+    _provider: provider,
+  })
+}
+
+impl OtlpMetrics {
+  /// Update the gauges from a fresh reading, reusing the Prometheus metric
+  /// names and `variant`/`unit` attributes.
+  pub fn record(&self, m: &Measurement) {
+    let mass = |variant: &'static str, value: f32| {
+      self.mass_concentration.record(
+        value as f64,
+        &[KeyValue::new("variant", variant), KeyValue::new("unit", MASS_UNIT)],
+      );
+    };
+    mass("PM1.0", m.mass.pm1);
+    mass("PM2.5", m.mass.pm25);
+    mass("PM4", m.mass.pm4);
+    mass("PM10", m.mass.pm10);
+
+    let number = |variant: &'static str, value: f32| {
+      self.number_concentration.record(
+        value as f64,
+        &[KeyValue::new("variant", variant), KeyValue::new("unit", NUMBER_UNIT)],
+      );
+    };
+    number("PM0.5", m.number.pm05);
+    number("PM1.0", m.number.pm1);
+    number("PM2.5", m.number.pm25);
+    number("PM4", m.number.pm4);
+    number("PM10", m.number.pm10);
+
+    self.typical_particle_size.record(
+      m.typical_particle_size as f64,
+      &[KeyValue::new("unit", PARTICLE_SIZE_UNIT)],
+    );
+  }
+
+  /// Flush and shut the provider down so the final batch is pushed before the
+  /// process exits.
+  pub fn shutdown(&self) {
+    if let Err(e) = self._provider.shutdown() {
+      tracing::warn!("could not shut down OTLP provider: {:?}", e);
+    }
+  }
+}
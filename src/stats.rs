@@ -0,0 +1,221 @@
+use std::collections::VecDeque;
+
+use crate::Measurement;
+
+/// Fixed-point scale applied to readings before bucketing. Values in μg/m³ or
+/// 1/cm³ are multiplied by this and floored so the histogram resolves to tenths.
+const SCALE: f32 = 10.0;
+
+/// Largest bucket index we'll allocate. A corrupt or out-of-range UART reading
+/// (e.g. `f32::INFINITY` or `1e20`) is clamped here rather than sizing `counts`
+/// off an untrusted float. 2000 buckets ≈ 200 μg/m³ at `SCALE`, well past any
+/// real reading.
+const MAX_BUCKET: usize = 2000;
+
+/// An HDR-histogram-style sliding window over one channel. Readings are scaled
+/// to integer buckets; the `window` queue remembers the last `capacity` bucket
+/// indices so the oldest can be evicted, and `sum` tracks the running total for
+/// the mean.
+pub struct Histogram {
+  counts: Vec<u32>,
+  window: VecDeque<usize>,
+  capacity: usize,
+  sum: u64,
+}
+
+impl Histogram {
+  pub fn new(capacity: usize) -> Histogram {
+    Histogram {
+      counts: Vec::new(),
+      window: VecDeque::with_capacity(capacity),
+      capacity,
+      sum: 0,
+    }
+  }
+
+  fn bucket(value: f32) -> usize {
+    // `!(value > 0.0)` also rejects NaN, which would otherwise cast to 0.
+    if !(value > 0.0) {
+      return 0;
+    }
+
+    let scaled = (value * SCALE).floor();
+    if scaled >= MAX_BUCKET as f32 {
+      MAX_BUCKET
+    } else {
+      scaled as usize
+    }
+  }
+
+  /// Record a reading, evicting the oldest sample if the window is full.
+  pub fn record(&mut self, value: f32) {
+    if self.capacity == 0 {
+      return;
+    }
+
+    if self.window.len() == self.capacity {
+      if let Some(old) = self.window.pop_front() {
+        self.counts[old] -= 1;
+        self.sum -= old as u64;
+      }
+    }
+
+    let bucket = Self::bucket(value);
+    if bucket >= self.counts.len() {
+      self.counts.resize(bucket + 1, 0);
+    }
+    self.counts[bucket] += 1;
+    self.window.push_back(bucket);
+    self.sum += bucket as u64;
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.window.is_empty()
+  }
+
+  pub fn min(&self) -> f32 {
+    self
+      .counts
+      .iter()
+      .position(|&c| c > 0)
+      .map(|i| i as f32 / SCALE)
+      .unwrap_or(0.0)
+  }
+
+  pub fn max(&self) -> f32 {
+    self
+      .counts
+      .iter()
+      .rposition(|&c| c > 0)
+      .map(|i| i as f32 / SCALE)
+      .unwrap_or(0.0)
+  }
+
+  pub fn mean(&self) -> f32 {
+    let total = self.window.len();
+    if total == 0 {
+      0.0
+    } else {
+      self.sum as f32 / total as f32 / SCALE
+    }
+  }
+
+  /// The `p`th percentile, walking cumulative counts to the
+  /// `ceil(p/100 * total)`th sample.
+  pub fn percentile(&self, p: u8) -> f32 {
+    let total = self.window.len();
+    if total == 0 {
+      return 0.0;
+    }
+
+    let target = (((p as f32 / 100.0) * total as f32).ceil() as u32).max(1);
+    let mut cumulative = 0u32;
+    for (i, &count) in self.counts.iter().enumerate() {
+      cumulative += count;
+      if cumulative >= target {
+        return i as f32 / SCALE;
+      }
+    }
+
+    self.max()
+  }
+}
+
+/// A sliding-window histogram per concentration channel.
+pub struct Stats {
+  pub mass_pm1: Histogram,
+  pub mass_pm25: Histogram,
+  pub mass_pm4: Histogram,
+  pub mass_pm10: Histogram,
+
+  pub number_pm05: Histogram,
+  pub number_pm1: Histogram,
+  pub number_pm25: Histogram,
+  pub number_pm4: Histogram,
+  pub number_pm10: Histogram,
+}
+
+impl Stats {
+  pub fn new(window: usize) -> Stats {
+    Stats {
+      mass_pm1: Histogram::new(window),
+      mass_pm25: Histogram::new(window),
+      mass_pm4: Histogram::new(window),
+      mass_pm10: Histogram::new(window),
+      number_pm05: Histogram::new(window),
+      number_pm1: Histogram::new(window),
+      number_pm25: Histogram::new(window),
+      number_pm4: Histogram::new(window),
+      number_pm10: Histogram::new(window),
+    }
+  }
+
+  pub fn record(&mut self, m: &Measurement) {
+    self.mass_pm1.record(m.mass.pm1);
+    self.mass_pm25.record(m.mass.pm25);
+    self.mass_pm4.record(m.mass.pm4);
+    self.mass_pm10.record(m.mass.pm10);
+
+    self.number_pm05.record(m.number.pm05);
+    self.number_pm1.record(m.number.pm1);
+    self.number_pm25.record(m.number.pm25);
+    self.number_pm4.record(m.number.pm4);
+    self.number_pm10.record(m.number.pm10);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn tracks_min_max_mean() {
+    let mut h = Histogram::new(8);
+    for v in [1.0, 2.0, 3.0, 4.0] {
+      h.record(v);
+    }
+    assert_eq!(h.min(), 1.0);
+    assert_eq!(h.max(), 4.0);
+    assert_eq!(h.mean(), 2.5);
+  }
+
+  #[test]
+  fn evicts_oldest_when_full() {
+    let mut h = Histogram::new(2);
+    h.record(1.0);
+    h.record(2.0);
+    h.record(3.0);
+    // The 1.0 reading has aged out of the two-sample window.
+    assert_eq!(h.min(), 2.0);
+    assert_eq!(h.max(), 3.0);
+  }
+
+  #[test]
+  fn percentiles_walk_cumulative_counts() {
+    let mut h = Histogram::new(100);
+    for i in 1..=100 {
+      h.record(i as f32);
+    }
+    assert_eq!(h.percentile(50), 50.0);
+    assert_eq!(h.percentile(90), 90.0);
+    assert_eq!(h.percentile(99), 99.0);
+  }
+
+  #[test]
+  fn clamps_garbage_readings() {
+    let mut h = Histogram::new(4);
+    h.record(f32::INFINITY);
+    h.record(f32::NAN);
+    h.record(1e20);
+    // Non-finite and huge readings are clamped, not allocated verbatim.
+    assert_eq!(h.max(), MAX_BUCKET as f32 / SCALE);
+  }
+
+  #[test]
+  fn empty_histogram_is_zeroed() {
+    let h = Histogram::new(4);
+    assert!(h.is_empty());
+    assert_eq!(h.percentile(99), 0.0);
+    assert_eq!(h.mean(), 0.0);
+  }
+}